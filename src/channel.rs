@@ -0,0 +1,287 @@
+use crate::{identity::Identity, passport::Passport};
+use futures::{sink::SinkExt as _, stream::StreamExt as _};
+use keynesis::{network::Connection, passport::EventCodec, PublicIdentity};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{io::Write as _, net::SocketAddr};
+use tokio::{
+    io::{self, AsyncReadExt as _, AsyncWriteExt as _},
+    net::{TcpListener, TcpStream},
+};
+use tokio_util::codec::{FramedRead, FramedWrite};
+
+/// upper bound on how many passport events `recv_passport` will allocate
+/// room for up front
+///
+/// `recv_passport` runs before the peer's passport has been checked
+/// against anything, so a bound here caps the allocation a peer can force
+/// with a single crafted `count` to something a real passport would never
+/// approach.
+const MAX_PASSPORT_EVENTS: u32 = 1 << 16;
+
+/// upper bound on the length, in bytes, of a single `read_json` message
+///
+/// caps the allocation a peer can force with a single crafted length
+/// prefix, before the bytes behind it have even been parsed.
+const MAX_JSON_MESSAGE_LEN: u32 = 8 * 1024 * 1024;
+
+/// connect to a listening peer and open a secure channel with it
+///
+/// `address` is dialed directly: there is no discovery here, the address
+/// of a `listen`ing peer has to be known ahead of time.
+///
+/// `pinned`, if given, is the remote's passport from a previous session:
+/// the peer is only trusted if it presents a passport with the same
+/// fingerprint (root event) again. with no `pinned` passport, the peer is
+/// trusted for this session only, after the operator interactively
+/// confirms its fingerprint; that answer is not stored anywhere.
+pub async fn connect(
+    identity: &Identity,
+    passport: &Passport,
+    pinned: Option<&Passport>,
+    address: SocketAddr,
+) -> io::Result<()> {
+    let (mut connection, remote_id) = dial(identity, address).await?;
+    authenticate(&mut connection, &remote_id, passport, pinned).await?;
+
+    eprintln!("secure channel established with {}", remote_id);
+    relay(connection).await
+}
+
+/// accept a single incoming connection on `address` and open a secure
+/// channel with it
+///
+/// see `connect` for the meaning of `pinned`.
+pub async fn listen(
+    identity: &Identity,
+    passport: &Passport,
+    pinned: Option<&Passport>,
+    address: SocketAddr,
+) -> io::Result<()> {
+    let (mut connection, remote_id) = accept(identity, address).await?;
+    authenticate(&mut connection, &remote_id, passport, pinned).await?;
+
+    eprintln!("secure channel established with {}", remote_id);
+    relay(connection).await
+}
+
+/// dial `address` and run the Noise handshake as the initiator
+pub(crate) async fn dial(
+    identity: &Identity,
+    address: SocketAddr,
+) -> io::Result<(Connection, PublicIdentity)> {
+    let stream = TcpStream::connect(address).await?;
+
+    Connection::connect(stream, identity.private_key())
+        .await
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+}
+
+/// wait for a single incoming connection on `address` and run the Noise
+/// handshake as the responder
+pub(crate) async fn accept(
+    identity: &Identity,
+    address: SocketAddr,
+) -> io::Result<(Connection, PublicIdentity)> {
+    let listener = TcpListener::bind(address).await?;
+    let (stream, peer) = listener.accept().await?;
+    eprintln!("connection from {}", peer);
+
+    Connection::accept(stream, identity.private_key())
+        .await
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+}
+
+/// exchange passports over an already Noise-authenticated `connection`,
+/// check that the peer's handshake identity is one it actually declared,
+/// and check the peer's passport itself against `pinned`
+///
+/// the Noise handshake only proves the peer controls `remote_id`'s private
+/// key; a peer is free to self-issue a brand new passport declaring that
+/// same `remote_id` active. so `remote_id` being active in the passport
+/// the peer sends is necessary but not sufficient: without also pinning
+/// the passport, any stranger with a fresh keypair could complete both
+/// checks. `pinned` is the remote's passport as trusted from a previous
+/// session; with none given, the operator is asked to confirm the peer's
+/// passport fingerprint interactively instead.
+///
+/// returns the peer's passport on success.
+pub(crate) async fn authenticate<S>(
+    connection: &mut S,
+    remote_id: &PublicIdentity,
+    own_passport: &Passport,
+    pinned: Option<&Passport>,
+) -> io::Result<Passport>
+where
+    S: io::AsyncRead + io::AsyncWrite + Unpin,
+{
+    send_passport(connection, own_passport).await?;
+    let remote_passport = recv_passport(connection).await?;
+
+    if !remote_passport.is_active(remote_id) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "{} authenticated the Noise handshake but is not an active identity \
+                 of the passport it sent: refusing the channel",
+                remote_id
+            ),
+        ));
+    }
+
+    match pinned {
+        Some(pinned) if pinned.fingerprint() == remote_passport.fingerprint() => {}
+        Some(pinned) => {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "{} presented passport {} but the pinned passport for it is {}: \
+                     refusing the channel",
+                    remote_id,
+                    remote_passport.fingerprint(),
+                    pinned.fingerprint()
+                ),
+            ));
+        }
+        None => confirm_unpinned_passport(remote_id, &remote_passport)?,
+    }
+
+    Ok(remote_passport)
+}
+
+/// ask the operator, on stderr/stdin, whether to trust a remote passport
+/// that was not pinned ahead of time
+///
+/// the channel is gated on the operator actually reading and accepting
+/// the fingerprint, rather than silently trusting whatever the peer sent.
+/// the answer only applies to the current session and is not remembered.
+fn confirm_unpinned_passport(remote_id: &PublicIdentity, passport: &Passport) -> io::Result<()> {
+    eprint!(
+        "no pinned passport for {}; it presents passport {}. trust it for this \
+         session? [y/N] ",
+        remote_id,
+        passport.fingerprint()
+    );
+    std::io::stderr().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+
+    if answer.trim().eq_ignore_ascii_case("y") {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("refusing to trust {} without a pinned passport", remote_id),
+        ))
+    }
+}
+
+async fn send_passport<S>(connection: &mut S, passport: &Passport) -> io::Result<()>
+where
+    S: io::AsyncWrite + Unpin,
+{
+    let events = passport.events();
+    connection.write_u32(events.len() as u32).await?;
+
+    let mut writer = FramedWrite::new(connection, EventCodec);
+    for event in &events {
+        writer
+            .send(event)
+            .await
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    }
+
+    Ok(())
+}
+
+async fn recv_passport<S>(connection: &mut S) -> io::Result<Passport>
+where
+    S: io::AsyncRead + Unpin,
+{
+    let count = connection.read_u32().await?;
+    if count > MAX_PASSPORT_EVENTS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "peer claims a passport of {} events, refusing to allocate for more than {}",
+                count, MAX_PASSPORT_EVENTS
+            ),
+        ));
+    }
+
+    let mut reader = FramedRead::new(connection, EventCodec);
+    let mut events = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let event = reader.next().await.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "peer closed the channel before sending all of its passport events",
+            )
+        })?;
+        events.push(event.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?);
+    }
+
+    Passport::from_events(events)
+}
+
+/// write a length-prefixed, JSON encoded message over `connection`
+///
+/// used by the `sync` reconciliation protocol to exchange event id lists
+/// and event batches once a channel is authenticated.
+pub(crate) async fn write_json<S, T>(connection: &mut S, value: &T) -> io::Result<()>
+where
+    S: io::AsyncWrite + Unpin,
+    T: Serialize,
+{
+    let bytes =
+        serde_json::to_vec(value).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+
+    connection.write_u32(bytes.len() as u32).await?;
+    connection.write_all(&bytes).await
+}
+
+/// read back a message written by `write_json`
+pub(crate) async fn read_json<S, T>(connection: &mut S) -> io::Result<T>
+where
+    S: io::AsyncRead + Unpin,
+    T: DeserializeOwned,
+{
+    let len = connection.read_u32().await?;
+    if len > MAX_JSON_MESSAGE_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "peer claims a {} byte message, refusing to allocate for more than {}",
+                len, MAX_JSON_MESSAGE_LEN
+            ),
+        ));
+    }
+
+    let mut bytes = vec![0; len as usize];
+    connection.read_exact(&mut bytes).await?;
+
+    serde_json::from_slice(&bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// relay standard input/output over the now-authenticated channel
+///
+/// returns once the peer has closed its side of the channel and standard
+/// input has reached its end: closing only one of the two does not stop
+/// the relay.
+async fn relay<S>(connection: S) -> io::Result<()>
+where
+    S: io::AsyncRead + io::AsyncWrite + Unpin,
+{
+    let (mut from_peer, mut to_peer) = io::split(connection);
+
+    let outbound = tokio::spawn(async move {
+        let mut stdin = io::stdin();
+        io::copy(&mut stdin, &mut to_peer).await
+    });
+
+    let mut stdout = io::stdout();
+    io::copy(&mut from_peer, &mut stdout).await?;
+
+    outbound.await??;
+    Ok(())
+}
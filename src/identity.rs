@@ -1,7 +1,32 @@
-use keynesis::{PrivateIdentity, PublicIdentity, Seed};
-use rand_core::{CryptoRng, RngCore};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use keynesis::{memsec::Scrubbed as _, PrivateIdentity, PublicIdentity, Seed, Signature};
+use rand_core::{CryptoRng, OsRng, RngCore};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    mpsc, Arc,
+};
 use tokio::io;
 
+/// the alphabet used by `PublicIdentity`'s textual (bech32) representation
+///
+/// used to reject a vanity `--prefix` up front instead of searching forever
+/// for a character that can never appear.
+const PUBLIC_IDENTITY_ALPHABET: &str = "qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// marks an identity file as an encrypted envelope rather than the legacy
+/// plaintext hex seed
+///
+/// this can never be confused with the legacy format: the legacy format is
+/// `Seed::SIZE * 2` lowercase hex characters, and this magic is not valid
+/// hex.
+const ENVELOPE_MAGIC: &[u8; 4] = b"KLI\0";
+const ENVELOPE_VERSION: u8 = 1;
+const ENVELOPE_SALT_SIZE: usize = 16;
+const ENVELOPE_NONCE_SIZE: usize = 12;
+
 #[derive(Debug)]
 pub struct Identity {
     seed: Seed,
@@ -20,29 +45,235 @@ impl Identity {
         Self { seed, identity }
     }
 
-    pub async fn import<IN>(reader: &mut IN) -> io::Result<Self>
+    /// deterministically recover the `Identity` derived from a human
+    /// memorable passphrase (a "brainwallet")
+    ///
+    /// the passphrase is stretched into a 32bytes seed with
+    /// [`crate::random::Seed::from_passphrase`] (Argon2id, so guessing the
+    /// passphrase is expensive) and the `Identity` is then built exactly as
+    /// `generate_new` would build it from any other seed. the intermediate
+    /// buffer holding the derived seed is wrapped in `crate::random::Seed`,
+    /// so it is scrubbed from memory on drop just like any other seed.
+    pub fn recover_from_passphrase(passphrase: &str) -> Self {
+        let derived = crate::random::Seed::from_passphrase(passphrase);
+
+        let mut bytes = [0; Seed::SIZE];
+        bytes.copy_from_slice(derived.as_bytes());
+
+        let seed = Seed::from(bytes);
+        let identity = PrivateIdentity::from_seed(seed.clone());
+        Self { seed, identity }
+    }
+
+    /// brute force an `Identity` whose `PublicIdentity` textual form starts
+    /// with the given `prefix`
+    ///
+    /// spawns one worker thread per available core; each draws 32 random
+    /// bytes from `OsRng`, builds the corresponding `Identity` and checks
+    /// the prefix against its public identity. the first worker to find a
+    /// match flips a shared `AtomicBool` so the others stop, and sends its
+    /// winning `Identity` back over a channel.
+    ///
+    /// # Panic
+    ///
+    /// panics if `prefix` contains a character that can never appear in a
+    /// `PublicIdentity`'s textual form, since the search would then never
+    /// terminate.
+    pub fn search_prefix(prefix: &str) -> Self {
+        // `PublicIdentity`'s textual form is always lowercase bech32, so
+        // normalize once here and search for the lowercase prefix: this
+        // keeps the validation loop below and the `starts_with` check in
+        // the worker threads consistent with each other.
+        let prefix = prefix.to_lowercase();
+
+        for c in prefix.chars() {
+            assert!(
+                PUBLIC_IDENTITY_ALPHABET.contains(c),
+                "prefix contains '{}', which cannot appear in a public identity",
+                c
+            );
+        }
+
+        let workers = std::thread::available_parallelism()
+            .map(std::num::NonZeroUsize::get)
+            .unwrap_or(1);
+
+        let found = Arc::new(AtomicBool::new(false));
+        let tries = Arc::new(AtomicU64::new(0));
+        let (winner, rx) = mpsc::channel();
+
+        for _ in 0..workers {
+            let prefix = prefix.to_owned();
+            let found = Arc::clone(&found);
+            let tries = Arc::clone(&tries);
+            let winner = winner.clone();
+
+            std::thread::spawn(move || {
+                while !found.load(Ordering::Relaxed) {
+                    let mut bytes = [0; Seed::SIZE];
+                    OsRng.fill_bytes(&mut bytes);
+
+                    let seed = Seed::from(bytes);
+                    let identity = PrivateIdentity::from_seed(seed.clone());
+                    tries.fetch_add(1, Ordering::Relaxed);
+
+                    if identity
+                        .public_id()
+                        .to_string()
+                        .starts_with(prefix.as_str())
+                    {
+                        found.store(true, Ordering::Relaxed);
+                        let _ = winner.send(Self { seed, identity });
+                        break;
+                    }
+                }
+            });
+        }
+        drop(winner);
+
+        let progress = std::thread::spawn({
+            let found = Arc::clone(&found);
+            let tries = Arc::clone(&tries);
+            move || {
+                let mut last = 0;
+                while !found.load(Ordering::Relaxed) {
+                    std::thread::sleep(std::time::Duration::from_secs(1));
+                    let now = tries.load(Ordering::Relaxed);
+                    eprintln!("{} tries/sec ({} tries so far)", now - last, now);
+                    last = now;
+                }
+            }
+        });
+
+        let identity = rx.recv().expect("a worker always eventually finds a match");
+        let _ = progress.join();
+        identity
+    }
+
+    /// read back an `Identity` previously written by `export`
+    ///
+    /// sniffs the leading bytes for `ENVELOPE_MAGIC`: if present, the file
+    /// is the encrypted envelope written by `export(.., Some(passphrase))`
+    /// and `passphrase` is required to decrypt it; otherwise this falls
+    /// back to the legacy plaintext hex format for compatibility.
+    pub async fn import<IN>(reader: &mut IN, passphrase: Option<&str>) -> io::Result<Self>
     where
         IN: io::AsyncReadExt + Unpin,
     {
-        let mut bytes = [0; Seed::SIZE * 2];
-        let mut seed = [0; Seed::SIZE];
-        reader.read_exact(&mut bytes).await?;
+        let mut magic = [0; ENVELOPE_MAGIC.len()];
+        reader.read_exact(&mut magic).await?;
+
+        let seed = if magic == *ENVELOPE_MAGIC {
+            let mut version = [0; 1];
+            reader.read_exact(&mut version).await?;
+            if version[0] != ENVELOPE_VERSION {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unsupported identity envelope version {}", version[0]),
+                ));
+            }
+
+            let mut salt = [0; ENVELOPE_SALT_SIZE];
+            reader.read_exact(&mut salt).await?;
+            let mut nonce = [0; ENVELOPE_NONCE_SIZE];
+            reader.read_exact(&mut nonce).await?;
+            let mut ciphertext = Vec::new();
+            reader.read_to_end(&mut ciphertext).await?;
 
-        hex::decode_to_slice(bytes.as_ref(), &mut seed)
-            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            let passphrase = passphrase.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "this identity is encrypted, a passphrase is required (--passphrase)",
+                )
+            })?;
+
+            let mut key = derive_key(passphrase, &salt);
+            let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+            key.scrub();
+            let mut plaintext = cipher
+                .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+                .map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "failed to decrypt identity: wrong passphrase or corrupted file",
+                    )
+                })?;
+
+            let mut seed = [0; Seed::SIZE];
+            seed.copy_from_slice(&plaintext);
+            plaintext.scrub();
+            seed
+        } else {
+            // legacy plaintext hex format: the magic we just read is
+            // actually the first bytes of the hex string.
+            let mut rest = vec![0; Seed::SIZE * 2 - magic.len()];
+            reader.read_exact(&mut rest).await?;
+
+            let mut hex_seed = Vec::with_capacity(Seed::SIZE * 2);
+            hex_seed.extend_from_slice(&magic);
+            hex_seed.extend_from_slice(&rest);
+
+            let mut seed = [0; Seed::SIZE];
+            hex::decode_to_slice(hex_seed.as_slice(), &mut seed)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            seed
+        };
 
         let seed = Seed::from(seed);
         let identity = PrivateIdentity::from_seed(seed.clone());
         Ok(Self { seed, identity })
     }
 
-    pub async fn export<OUT>(&self, out: &mut OUT) -> io::Result<u64>
+    /// write this `Identity` out so it can later be read back by `import`
+    ///
+    /// without a `passphrase` this writes the legacy plaintext hex seed,
+    /// unchanged from before. with a `passphrase`, the seed is instead
+    /// encrypted: a 32bytes key is derived from the passphrase with
+    /// Argon2id over a random salt, and the seed is sealed with
+    /// ChaCha20-Poly1305 under a random nonce, framed as
+    /// `MAGIC || version || salt || nonce || ciphertext`.
+    pub async fn export<OUT>(&self, out: &mut OUT, passphrase: Option<&str>) -> io::Result<u64>
     where
         OUT: io::AsyncWrite + Unpin,
     {
-        let export = self.seed.to_string();
-        let mut export = export.as_bytes();
-        io::copy(&mut export, out).await
+        match passphrase {
+            None => {
+                let export = self.seed.to_string();
+                let mut export = export.as_bytes();
+                io::copy(&mut export, out).await
+            }
+            Some(passphrase) => {
+                let mut seed_bytes = [0u8; Seed::SIZE];
+                hex::decode_to_slice(self.seed.to_string(), &mut seed_bytes)
+                    .expect("a Seed's hex representation always round-trips");
+
+                let mut salt = [0; ENVELOPE_SALT_SIZE];
+                OsRng.fill_bytes(&mut salt);
+                let mut nonce = [0; ENVELOPE_NONCE_SIZE];
+                OsRng.fill_bytes(&mut nonce);
+
+                let mut key = derive_key(passphrase, &salt);
+                let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+                key.scrub();
+                let ciphertext = cipher
+                    .encrypt(Nonce::from_slice(&nonce), seed_bytes.as_ref())
+                    .expect("encrypting under a freshly generated nonce cannot fail");
+                seed_bytes.scrub();
+
+                let mut envelope = Vec::with_capacity(
+                    ENVELOPE_MAGIC.len() + 1 + ENVELOPE_SALT_SIZE + ENVELOPE_NONCE_SIZE
+                        + ciphertext.len(),
+                );
+                envelope.extend_from_slice(ENVELOPE_MAGIC);
+                envelope.push(ENVELOPE_VERSION);
+                envelope.extend_from_slice(&salt);
+                envelope.extend_from_slice(&nonce);
+                envelope.extend_from_slice(&ciphertext);
+
+                let mut export = envelope.as_slice();
+                io::copy(&mut export, out).await
+            }
+        }
     }
 
     pub(crate) fn private_key(&self) -> &PrivateIdentity {
@@ -52,4 +283,25 @@ impl Identity {
     pub fn public_id(&self) -> PublicIdentity {
         self.identity.public_id()
     }
+
+    /// sign an arbitrary message with this identity's private key
+    ///
+    /// this produces a detached `Signature`: the message itself is not
+    /// part of the output. a verifier needs the original message, this
+    /// `Signature`, and this identity's `PublicIdentity` to check it with
+    /// `PublicIdentity::verify`.
+    pub fn sign(&self, message: &[u8]) -> Signature {
+        self.identity.sign(message)
+    }
+}
+
+/// derive the 32bytes symmetric key used to seal an identity envelope
+fn derive_key(passphrase: &str, salt: &[u8]) -> [u8; 32] {
+    let argon2 = crate::random::argon2id();
+
+    let mut key = [0; 32];
+    argon2
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .expect("hardcoded argon2id parameters are valid");
+    key
 }
@@ -1,17 +1,21 @@
+mod channel;
 mod identity;
 mod passport;
 mod random;
+mod sync;
 
 use self::{identity::Identity, passport::Passport, random::Seed};
 use keynesis::{
     passport::{Event, EventId},
-    PublicIdentity,
+    PublicIdentity, Signature,
 };
+use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::str::FromStr as _;
 use structopt::StructOpt;
 use tokio::{
     fs,
-    io::{self, AsyncWrite},
+    io::{self, AsyncReadExt as _, AsyncWrite},
 };
 
 /// Keynesis command line tool
@@ -26,6 +30,76 @@ enum Kli {
     Passport(PassportCommand),
     /// Create new events
     Event(EventCommand),
+    /// Open a mutually authenticated, encrypted channel with another passport
+    Channel(ChannelCommand),
+}
+
+#[derive(Debug, StructOpt)]
+enum ChannelCommand {
+    /// connect to a peer that is `listen`ing and open a secure channel
+    ///
+    /// once the channel is established, standard input is relayed to the
+    /// peer and whatever the peer sends is relayed to standard output.
+    Connect {
+        /// set the path where stored the identity is.
+        #[structopt(long, env = "KLI_IDENTITY")]
+        identity: PathBuf,
+
+        /// the passphrase to decrypt the identity with, if it is stored
+        /// encrypted. ignored for plaintext identities.
+        #[structopt(long, env = "KLI_PASSPHRASE", hide_env_values = true)]
+        passphrase: Option<String>,
+
+        /// set the path to where our own passport is.
+        #[structopt(long, env = "KLI_PASSPORT")]
+        passport: PathBuf,
+
+        /// pin the remote's expected passport, from a copy saved in an
+        /// earlier session.
+        ///
+        /// if not given, you will be asked to confirm the peer's passport
+        /// fingerprint interactively and it will not be remembered for
+        /// later sessions: a peer authenticating the Noise handshake is
+        /// not enough on its own, since anyone can self-issue a passport
+        /// declaring their own identity active.
+        #[structopt(long, env = "KLI_REMOTE_PASSPORT")]
+        remote_passport: Option<PathBuf>,
+
+        /// the address of the peer to connect to
+        address: SocketAddr,
+    },
+    /// wait for a single peer to `connect` and open a secure channel
+    ///
+    /// once the channel is established, standard input is relayed to the
+    /// peer and whatever the peer sends is relayed to standard output.
+    Listen {
+        /// set the path where stored the identity is.
+        #[structopt(long, env = "KLI_IDENTITY")]
+        identity: PathBuf,
+
+        /// the passphrase to decrypt the identity with, if it is stored
+        /// encrypted. ignored for plaintext identities.
+        #[structopt(long, env = "KLI_PASSPHRASE", hide_env_values = true)]
+        passphrase: Option<String>,
+
+        /// set the path to where our own passport is.
+        #[structopt(long, env = "KLI_PASSPORT")]
+        passport: PathBuf,
+
+        /// pin the remote's expected passport, from a copy saved in an
+        /// earlier session.
+        ///
+        /// if not given, you will be asked to confirm the peer's passport
+        /// fingerprint interactively and it will not be remembered for
+        /// later sessions: a peer authenticating the Noise handshake is
+        /// not enough on its own, since anyone can self-issue a passport
+        /// declaring their own identity active.
+        #[structopt(long, env = "KLI_REMOTE_PASSPORT")]
+        remote_passport: Option<PathBuf>,
+
+        /// the address to listen on
+        address: SocketAddr,
+    },
 }
 
 #[derive(Debug, StructOpt)]
@@ -40,6 +114,11 @@ enum PassportCommand {
         #[structopt(long, env = "KLI_IDENTITY")]
         identity: PathBuf,
 
+        /// the passphrase to decrypt the identity with, if it is stored
+        /// encrypted. ignored for plaintext identities.
+        #[structopt(long, env = "KLI_PASSPHRASE", hide_env_values = true)]
+        passphrase: Option<String>,
+
         /// set the path to store the new passport
         ///
         /// if none is given, then the passport will be printed
@@ -77,6 +156,47 @@ enum PassportCommand {
         /// read from the standard input if nothing given
         event: Option<PathBuf>,
     },
+    /// reconcile this passport with the copy held by a peer
+    ///
+    /// instead of manually `Load`ing each other's events one file at a
+    /// time, connect to the peer (over the same secure channel as `Kli
+    /// Channel`) and exchange whichever events each side is missing.
+    Sync {
+        /// set the path where stored the identity is.
+        #[structopt(long, env = "KLI_IDENTITY")]
+        identity: PathBuf,
+
+        /// the passphrase to decrypt the identity with, if it is stored
+        /// encrypted. ignored for plaintext identities.
+        #[structopt(long, env = "KLI_PASSPHRASE", hide_env_values = true)]
+        passphrase: Option<String>,
+
+        /// set the path to where the passport is.
+        #[structopt(long, env = "KLI_PASSPORT")]
+        passport: PathBuf,
+
+        /// pin the remote's expected passport, from a copy saved in an
+        /// earlier session.
+        ///
+        /// if not given, you will be asked to confirm the peer's passport
+        /// fingerprint interactively and it will not be remembered for
+        /// later sessions: a peer authenticating the Noise handshake is
+        /// not enough on its own, since anyone can self-issue a passport
+        /// declaring their own identity active.
+        #[structopt(long, env = "KLI_REMOTE_PASSPORT")]
+        remote_passport: Option<PathBuf>,
+
+        /// connect to `address` instead of listening on it
+        #[structopt(long)]
+        connect: bool,
+
+        /// print the events that would be added without writing anything
+        #[structopt(long)]
+        dry_run: bool,
+
+        /// the address to connect to, or to listen on
+        address: SocketAddr,
+    },
 }
 
 #[derive(Debug, StructOpt)]
@@ -101,6 +221,11 @@ enum EventCommand {
         #[structopt(long, env = "KLI_IDENTITY")]
         identity: PathBuf,
 
+        /// the passphrase to decrypt the identity with, if it is stored
+        /// encrypted. ignored for plaintext identities.
+        #[structopt(long, env = "KLI_PASSPHRASE", hide_env_values = true)]
+        passphrase: Option<String>,
+
         /// set the path to where the passport is.
         #[structopt(long, env = "KLI_PASSPORT")]
         passport: PathBuf,
@@ -127,6 +252,11 @@ enum EventCommand {
         #[structopt(long, env = "KLI_IDENTITY")]
         identity: PathBuf,
 
+        /// the passphrase to decrypt the identity with, if it is stored
+        /// encrypted. ignored for plaintext identities.
+        #[structopt(long, env = "KLI_PASSPHRASE", hide_env_values = true)]
+        passphrase: Option<String>,
+
         /// set the path to where the passport is.
         #[structopt(long, env = "KLI_PASSPORT")]
         passport: PathBuf,
@@ -147,6 +277,11 @@ enum EventCommand {
         #[structopt(long, env = "KLI_IDENTITY")]
         identity: PathBuf,
 
+        /// the passphrase to decrypt the identity with, if it is stored
+        /// encrypted. ignored for plaintext identities.
+        #[structopt(long, env = "KLI_PASSPHRASE", hide_env_values = true)]
+        passphrase: Option<String>,
+
         /// set the signature index, this matter regarding to how the
         /// event proof will be verified later
         ///
@@ -182,6 +317,12 @@ enum IdentityCommand {
         #[structopt(long, default_value)]
         seed: Seed,
 
+        /// encrypt the generated identity at rest with this passphrase
+        ///
+        /// if not set, the identity is stored as plaintext hex, as before.
+        #[structopt(long, env = "KLI_PASSPHRASE", hide_env_values = true)]
+        passphrase: Option<String>,
+
         /// set the path to store the generate identity.
         ///
         /// if none is given, then the identity's secret will be printed
@@ -198,6 +339,110 @@ enum IdentityCommand {
         /// from the standard output.
         #[structopt(long, env = "KLI_IDENTITY")]
         identity: Option<PathBuf>,
+
+        /// the passphrase to decrypt the identity with, if it is stored
+        /// encrypted. ignored for plaintext identities.
+        #[structopt(long, env = "KLI_PASSPHRASE", hide_env_values = true)]
+        passphrase: Option<String>,
+    },
+    /// Recover an identity from a memorable passphrase (a "brainwallet")
+    ///
+    /// this deterministically derives the same identity every time it
+    /// is given the same passphrase, so a lost identity file can be
+    /// reconstructed from the passphrase alone. the passphrase is
+    /// stretched with a memory-hard KDF (Argon2id), but the security
+    /// of the recovered identity still ultimately depends on how hard
+    /// the passphrase is to guess: prefer a long, unique passphrase.
+    Recover {
+        /// the passphrase to recover the identity from
+        ///
+        /// prefer a long, high entropy passphrase: this command will
+        /// warn if the given passphrase looks too weak, but it cannot
+        /// stop you from using it anyway.
+        #[structopt(long, env = "KLI_PHRASE", hide_env_values = true)]
+        phrase: String,
+
+        /// encrypt the recovered identity at rest with this passphrase
+        ///
+        /// if not set, the identity is stored as plaintext hex, as before.
+        /// this is independent from `--phrase`: the identity is still
+        /// deterministically derived from the recovery phrase, only its
+        /// on-disk storage is protected by this passphrase.
+        #[structopt(long, env = "KLI_PASSPHRASE", hide_env_values = true)]
+        passphrase: Option<String>,
+
+        /// set the path to store the recovered identity.
+        ///
+        /// if none is given, then the identity's secret will be printed
+        /// on the standard output.
+        #[structopt(env = "KLI_IDENTITY")]
+        identity: Option<PathBuf>,
+    },
+    /// Brute force an identity whose public identity starts with a prefix
+    ///
+    /// this is useful to obtain a memorable/recognizable public identity.
+    /// the search time grows exponentially with the prefix length, so
+    /// keep it short.
+    Prefix {
+        /// the prefix the public identity should start with
+        prefix: String,
+
+        /// encrypt the found identity at rest with this passphrase
+        ///
+        /// if not set, the identity is stored as plaintext hex, as before.
+        #[structopt(long, env = "KLI_PASSPHRASE", hide_env_values = true)]
+        passphrase: Option<String>,
+
+        /// set the path to store the generate identity.
+        ///
+        /// if none is given, then the identity's secret will be printed
+        /// on the standard output.
+        #[structopt(env = "KLI_IDENTITY")]
+        identity: Option<PathBuf>,
+    },
+    /// Sign an arbitrary message, producing a detached signature
+    ///
+    /// the signature does not embed the message: keep the message
+    /// around, it is needed again to `Verify` the signature.
+    Sign {
+        /// set the path where stored the identity is.
+        #[structopt(long, env = "KLI_IDENTITY")]
+        identity: Option<PathBuf>,
+
+        /// the passphrase to decrypt the identity with, if it is stored
+        /// encrypted. ignored for plaintext identities.
+        #[structopt(long, env = "KLI_PASSPHRASE", hide_env_values = true)]
+        passphrase: Option<String>,
+
+        /// set the path to the message to sign
+        ///
+        /// if nothing given, the message will be read from standard input
+        #[structopt(long)]
+        message: Option<PathBuf>,
+
+        /// set the path to store the detached signature (hex encoded)
+        ///
+        /// if none is given, then the signature will be printed on the
+        /// standard output.
+        #[structopt(long)]
+        signature: Option<PathBuf>,
+    },
+    /// Verify a detached signature against a message and a public identity
+    ///
+    /// exits with a non zero status if the signature does not verify.
+    Verify {
+        /// the public identity that is expected to have signed the message
+        public_id: PublicIdentity,
+
+        /// set the path to the message that was signed
+        ///
+        /// if nothing given, the message will be read from standard input
+        #[structopt(long)]
+        message: Option<PathBuf>,
+
+        /// set the path to the detached signature (hex encoded) to verify
+        #[structopt(long)]
+        signature: PathBuf,
     },
 }
 
@@ -206,24 +451,125 @@ async fn main() {
     let cli = Kli::from_args();
 
     match cli {
-        Kli::Identity(IdentityCommand::Info { identity }) => {
+        Kli::Identity(IdentityCommand::Info { identity, passphrase }) => {
             let mut reader = input_or_standard_input(&identity).await.unwrap();
-            let identity = Identity::import(&mut reader).await.unwrap();
+            let identity = Identity::import(&mut reader, passphrase.as_deref())
+                .await
+                .unwrap();
 
             println!("{} (Public Identity)", identity.public_id());
             println!("{} (Verify Key)", identity.public_id().verify_key());
         }
-        Kli::Identity(IdentityCommand::Generate { seed, identity }) => {
+        Kli::Identity(IdentityCommand::Generate { seed, passphrase, identity }) => {
             let mut rng = seed.into_cha_cha_rng();
             let mut writer = output_or_standard_output(&identity).await.unwrap();
 
             let identity = Identity::generate_new(&mut rng);
-            identity.export(&mut writer).await.unwrap();
+            identity
+                .export(&mut writer, passphrase.as_deref())
+                .await
+                .unwrap();
         }
-        Kli::Passport(PassportCommand::New { identity, passport }) => {
+        Kli::Identity(IdentityCommand::Recover { phrase, passphrase, identity }) => {
+            warn_on_low_passphrase_entropy(&phrase);
+
+            let mut writer = output_or_standard_output(&identity).await.unwrap();
+
+            let identity = Identity::recover_from_passphrase(&phrase);
+            identity
+                .export(&mut writer, passphrase.as_deref())
+                .await
+                .unwrap();
+        }
+        Kli::Identity(IdentityCommand::Prefix { prefix, passphrase, identity }) => {
+            let mut writer = output_or_standard_output(&identity).await.unwrap();
+
+            let identity = Identity::search_prefix(&prefix);
+            identity
+                .export(&mut writer, passphrase.as_deref())
+                .await
+                .unwrap();
+        }
+        Kli::Identity(IdentityCommand::Sign { identity, passphrase, message, signature: signature_output }) => {
+            let identity = {
+                let mut reader = input_or_standard_input(&identity).await.unwrap();
+                Identity::import(&mut reader, passphrase.as_deref())
+                    .await
+                    .unwrap()
+            };
+            let message = read_to_end(&message).await.unwrap();
+            let signature = identity.sign(&message);
+
+            let mut writer = output_or_standard_output(&signature_output).await.unwrap();
+            let mut export = signature.to_string().into_bytes();
+            io::copy(&mut export.as_slice(), &mut writer).await.unwrap();
+        }
+        Kli::Identity(IdentityCommand::Verify { public_id, message, signature }) => {
+            let message = read_to_end(&message).await.unwrap();
+
+            let signature_hex = read_to_end(&Some(signature)).await.unwrap();
+            let signature_hex = String::from_utf8(signature_hex).unwrap();
+            let signature = Signature::from_str(signature_hex.trim()).unwrap();
+
+            if public_id.verify(&message, &signature) {
+                println!("OK");
+            } else {
+                eprintln!("FAILED: signature does not match");
+                std::process::exit(1);
+            }
+        }
+        Kli::Channel(ChannelCommand::Connect { identity, passphrase, passport, remote_passport, address }) => {
             let identity = {
                 let mut reader = input_or_standard_input(&Some(identity)).await.unwrap();
-                Identity::import(&mut reader).await.unwrap()
+                Identity::import(&mut reader, passphrase.as_deref())
+                    .await
+                    .unwrap()
+            };
+            let passport = {
+                let mut reader = input_or_standard_input(&Some(passport)).await.unwrap();
+                Passport::import(&mut reader).await.unwrap()
+            };
+            let remote_passport = match remote_passport {
+                Some(path) => {
+                    let mut reader = input_or_standard_input(&Some(path)).await.unwrap();
+                    Some(Passport::import(&mut reader).await.unwrap())
+                }
+                None => None,
+            };
+
+            channel::connect(&identity, &passport, remote_passport.as_ref(), address)
+                .await
+                .unwrap();
+        }
+        Kli::Channel(ChannelCommand::Listen { identity, passphrase, passport, remote_passport, address }) => {
+            let identity = {
+                let mut reader = input_or_standard_input(&Some(identity)).await.unwrap();
+                Identity::import(&mut reader, passphrase.as_deref())
+                    .await
+                    .unwrap()
+            };
+            let passport = {
+                let mut reader = input_or_standard_input(&Some(passport)).await.unwrap();
+                Passport::import(&mut reader).await.unwrap()
+            };
+            let remote_passport = match remote_passport {
+                Some(path) => {
+                    let mut reader = input_or_standard_input(&Some(path)).await.unwrap();
+                    Some(Passport::import(&mut reader).await.unwrap())
+                }
+                None => None,
+            };
+
+            channel::listen(&identity, &passport, remote_passport.as_ref(), address)
+                .await
+                .unwrap();
+        }
+        Kli::Passport(PassportCommand::New { identity, passphrase, passport }) => {
+            let identity = {
+                let mut reader = input_or_standard_input(&Some(identity)).await.unwrap();
+                Identity::import(&mut reader, passphrase.as_deref())
+                    .await
+                    .unwrap()
             };
 
             let new_passport = Passport::new(&identity);
@@ -260,14 +606,46 @@ async fn main() {
             let mut writer = output_or_standard_output(&Some(passport)).await.unwrap();
             p.export(&mut writer).await.unwrap();
         }
-        Kli::Event(EventCommand::Declare { identity, new_identity, passport , event: event_output}) => {
+        Kli::Passport(PassportCommand::Sync { identity, passphrase, passport, remote_passport, connect, dry_run, address }) => {
+            let identity = {
+                let mut reader = input_or_standard_input(&Some(identity)).await.unwrap();
+                Identity::import(&mut reader, passphrase.as_deref())
+                    .await
+                    .unwrap()
+            };
+            let mut p = {
+                let mut reader = input_or_standard_input(&Some(passport.clone()))
+                    .await
+                    .unwrap();
+                Passport::import(&mut reader).await.unwrap()
+            };
+            let remote_passport = match remote_passport {
+                Some(path) => {
+                    let mut reader = input_or_standard_input(&Some(path)).await.unwrap();
+                    Some(Passport::import(&mut reader).await.unwrap())
+                }
+                None => None,
+            };
+
+            sync::sync(&identity, &mut p, remote_passport.as_ref(), address, connect, dry_run)
+                .await
+                .unwrap();
+
+            if !dry_run {
+                let mut writer = output_or_standard_output(&Some(passport)).await.unwrap();
+                p.export(&mut writer).await.unwrap();
+            }
+        }
+        Kli::Event(EventCommand::Declare { identity, passphrase, new_identity, passport , event: event_output}) => {
             let passport = {
                 let mut reader = input_or_standard_input(&Some(passport)).await.unwrap();
                 Passport::import(&mut reader).await.unwrap()
             };
             let identity = {
                 let mut reader = input_or_standard_input(&Some(identity)).await.unwrap();
-                Identity::import(&mut reader).await.unwrap()
+                Identity::import(&mut reader, passphrase.as_deref())
+                    .await
+                    .unwrap()
             };
 
             let event = passport.next_event_declare(&identity, new_identity);
@@ -279,14 +657,16 @@ async fn main() {
                 serde_json::to_writer_pretty(std::io::stdout(), &event).unwrap();
             };
         }
-        Kli::Event(EventCommand::Repudiate { identity, event_id, passport , event: event_output}) => {
+        Kli::Event(EventCommand::Repudiate { identity, passphrase, event_id, passport , event: event_output}) => {
             let passport = {
                 let mut reader = input_or_standard_input(&Some(passport)).await.unwrap();
                 Passport::import(&mut reader).await.unwrap()
             };
             let identity = {
                 let mut reader = input_or_standard_input(&Some(identity)).await.unwrap();
-                Identity::import(&mut reader).await.unwrap()
+                Identity::import(&mut reader, passphrase.as_deref())
+                    .await
+                    .unwrap()
             };
 
             let event = passport.next_event_repudiate(&identity, event_id);
@@ -298,10 +678,12 @@ async fn main() {
                 serde_json::to_writer_pretty(std::io::stdout(), &event).unwrap();
             };
         }
-        Kli::Event(EventCommand::ExtraSignature { identity, index, event: event_path}) => {
+        Kli::Event(EventCommand::ExtraSignature { identity, passphrase, index, event: event_path}) => {
             let identity = {
                 let mut reader = input_or_standard_input(&Some(identity)).await.unwrap();
-                Identity::import(&mut reader).await.unwrap()
+                Identity::import(&mut reader, passphrase.as_deref())
+                    .await
+                    .unwrap()
             };
             let mut event: Event = if let Some(path) = &event_path {
                 let file = std::fs::File::open(path).unwrap();
@@ -355,3 +737,40 @@ where
         Ok(Box::new(io::stdin()))
     }
 }
+
+/// read the whole content of the given path (or standard input, if none
+/// is given) into memory
+async fn read_to_end<P>(path: &Option<P>) -> io::Result<Vec<u8>>
+where
+    P: AsRef<std::path::Path>,
+{
+    let mut reader = input_or_standard_input(path).await?;
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes).await?;
+    Ok(bytes)
+}
+
+/// print a warning on the standard error if the given passphrase looks
+/// too weak to safely derive an identity from
+///
+/// this is only a rough estimate (the passphrase's length times the log2
+/// of the number of distinct characters it uses): good enough to catch obviously weak inputs, not
+/// a substitute for a proper strength meter.
+fn warn_on_low_passphrase_entropy(phrase: &str) {
+    const MIN_ENTROPY_BITS: f64 = 60.0;
+
+    let alphabet_size = phrase
+        .chars()
+        .collect::<std::collections::BTreeSet<_>>()
+        .len()
+        .max(1) as f64;
+    let estimated_bits = phrase.chars().count() as f64 * alphabet_size.log2();
+
+    if estimated_bits < MIN_ENTROPY_BITS {
+        eprintln!(
+            "warning: this passphrase is estimated to carry only ~{:.0} bits of entropy \
+             (recommended: at least {:.0}). anyone able to guess it can recover your identity.",
+            estimated_bits, MIN_ENTROPY_BITS
+        );
+    }
+}
@@ -23,6 +23,66 @@ impl Passport {
         self.passport.iter().cloned().collect()
     }
 
+    /// the `EventId`s of this passport, in passport order
+    pub fn event_ids(&self) -> Vec<EventId> {
+        self.passport.iter().map(Event::id).collect()
+    }
+
+    /// the `Event` with the given `EventId`, if this passport has it
+    pub fn get_event(&self, id: &EventId) -> Option<Event> {
+        self.passport.iter().find(|event| &event.id() == id).cloned()
+    }
+
+    /// the `PublicIdentity`s currently declared and not repudiated
+    pub fn active_ids(&self) -> Vec<PublicIdentity> {
+        self.passport.active_ids().cloned().collect()
+    }
+
+    /// whether the given `PublicIdentity` is currently declared and not
+    /// repudiated in this passport
+    pub fn is_active(&self, id: &PublicIdentity) -> bool {
+        self.active_ids().iter().any(|active| active == id)
+    }
+
+    /// a stable fingerprint for this passport: the `EventId` of its first
+    /// (root) event
+    ///
+    /// every later event is built on top of the root one, so this never
+    /// changes as the passport gains new events. used to pin a remote
+    /// passport across sessions (ssh `known_hosts` style) instead of
+    /// trusting whatever a peer claims about itself the first time it is
+    /// seen.
+    pub fn fingerprint(&self) -> EventId {
+        self.passport
+            .iter()
+            .next()
+            .expect("a Passport always has at least its root event")
+            .id()
+    }
+
+    /// rebuild a `Passport` from an ordered list of `Event`s, as received
+    /// over a `channel` connection
+    pub fn from_events(events: Vec<Event>) -> io::Result<Self> {
+        let mut events = events.into_iter();
+
+        let first = events.next().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Expecting to read the first entry of a passport",
+            )
+        })?;
+        let mut passport = keynesis::Passport::new_with(first)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        for event in events {
+            passport
+                .load_event(event)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        }
+
+        Ok(Self { passport })
+    }
+
     pub fn next_event_declare(&self, identity: &Identity, with: PublicIdentity) -> Event {
         let mut event = self.passport.prepare_next_event(
             EventAction::Declaration { with }
@@ -1,3 +1,4 @@
+use argon2::Argon2;
 use keynesis::memsec::Scrubbed as _;
 pub use rand_chacha::ChaChaRng;
 use rand_core::{OsRng, RngCore as _, SeedableRng as _};
@@ -5,6 +6,7 @@ use std::{
     fmt::{self, Display},
     str::FromStr,
 };
+use unicode_normalization::UnicodeNormalization as _;
 
 /// a Randomly generated seed or retrieved from a given input
 #[derive(Debug, Clone)]
@@ -49,6 +51,11 @@ impl Seed {
         hex::encode(&self.0)
     }
 
+    /// the raw bytes of this `Seed`
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
     /// from hexadecimal
     ///
     pub fn from_hex(s: &str) -> Result<Self, hex::FromHexError> {
@@ -69,6 +76,50 @@ impl Seed {
         seed[..self.0.len()].copy_from_slice(&self.0);
         ChaChaRng::from_seed(seed)
     }
+
+    /// deterministically derive a 256bits `Seed` from a human memorable
+    /// passphrase (a "brainwallet")
+    ///
+    /// the passphrase is first NFKC-normalized so that the same words
+    /// typed on different systems/keyboard layouts always produce the
+    /// same bytes, then stretched through Argon2id (memory-hard, so
+    /// brute forcing the passphrase is expensive) with a fixed,
+    /// application-specific salt. the salt does not need to be secret:
+    /// its only purpose is to keep this derivation from lining up with
+    /// Argon2id hashes produced for any other application.
+    ///
+    /// this is convenient to recover an identity from memory alone, but
+    /// the security of the resulting identity is only as good as the
+    /// passphrase: prefer a long, high entropy passphrase.
+    pub fn from_passphrase(passphrase: &str) -> Self {
+        const SALT: &[u8] = b"keynesis-cli/identity/brainwallet/v1";
+
+        let normalized: String = passphrase.nfkc().collect();
+        let argon2 = argon2id();
+
+        let mut seed = vec![0; 32];
+        argon2
+            .hash_password_into(normalized.as_bytes(), SALT, &mut seed)
+            .expect("hardcoded argon2id parameters are valid");
+
+        Self(seed)
+    }
+}
+
+/// the Argon2id parameters shared by every passphrase-derived secret in
+/// this CLI: `Seed::from_passphrase`'s brainwallet seed and
+/// `crate::identity`'s at-rest identity envelope key
+///
+/// kept in one place so the two can't silently drift apart if one is
+/// retuned later.
+pub(crate) fn argon2id() -> Argon2<'static> {
+    const MEMORY_KIB: u32 = 64 * 1024;
+    const ITERATIONS: u32 = 3;
+    const PARALLELISM: u32 = 1;
+
+    let params = argon2::Params::new(MEMORY_KIB, ITERATIONS, PARALLELISM, Some(32))
+        .expect("hardcoded argon2id parameters are valid");
+    Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
 }
 
 impl Drop for Seed {
@@ -0,0 +1,74 @@
+use crate::{
+    channel::{self, read_json, write_json},
+    identity::Identity,
+    passport::Passport,
+};
+use keynesis::passport::{Event, EventId};
+use std::{collections::HashSet, net::SocketAddr};
+use tokio::io;
+
+/// reconcile `passport` with the one held by a peer reachable at `address`
+///
+/// `dial` selects which side of the Noise handshake we take: `true` to
+/// `connect` to a `listen`ing peer, `false` to `listen` for one. Events the
+/// other side has that we don't are requested and, unless `dry_run` is set,
+/// applied (and vice versa, symmetrically, for the events we have that they
+/// don't).
+///
+/// see `channel::authenticate` for the meaning of `pinned`.
+pub async fn sync(
+    identity: &Identity,
+    passport: &mut Passport,
+    pinned: Option<&Passport>,
+    address: SocketAddr,
+    dial: bool,
+    dry_run: bool,
+) -> io::Result<()> {
+    let (mut connection, remote_id) = if dial {
+        channel::dial(identity, address).await?
+    } else {
+        channel::accept(identity, address).await?
+    };
+
+    let remote_passport =
+        channel::authenticate(&mut connection, &remote_id, passport, pinned).await?;
+    eprintln!("reconciling with {}", remote_id);
+
+    let local_ids: HashSet<EventId> = passport.event_ids().into_iter().collect();
+    let remote_ids = remote_passport.event_ids();
+
+    // the events the peer has that we are missing, kept in the peer's
+    // passport order so each event's prior state is applied first.
+    let missing_locally: Vec<EventId> = remote_ids
+        .into_iter()
+        .filter(|id| !local_ids.contains(id))
+        .collect();
+
+    write_json(&mut connection, &missing_locally).await?;
+    let peer_wants: Vec<EventId> = read_json(&mut connection).await?;
+
+    let to_send: Vec<Event> = peer_wants
+        .iter()
+        .filter_map(|id| passport.get_event(id))
+        .collect();
+    write_json(&mut connection, &to_send).await?;
+    let received: Vec<Event> = read_json(&mut connection).await?;
+
+    if dry_run {
+        for event in &received {
+            println!("{} (would be added)", event.id());
+        }
+        return Ok(());
+    }
+
+    for event in received {
+        let id = event.id();
+        if let Err(err) = passport.load_event(event) {
+            eprintln!("rejected event {}: {}", id, err);
+        } else {
+            println!("{} (added)", id);
+        }
+    }
+
+    Ok(())
+}